@@ -0,0 +1,3 @@
+pub mod sbrk;
+mod spin_lock;
+mod utils;
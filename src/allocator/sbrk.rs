@@ -0,0 +1,1010 @@
+use super::spin_lock::{Spinlock, SpinlockGuard};
+use super::utils;
+use libc::{c_void, pthread_getspecific, pthread_key_create, pthread_key_t, pthread_setspecific, sbrk};
+use std::cmp;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Once;
+use std::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    mem::{self, align_of, size_of},
+    ptr,
+};
+
+/// Fixed block sizes served by `SbrkAllocator::list_heads`. Requests that
+/// fit one of these are handled by a segregated free list in O(1); larger
+/// requests fall back to the general address-ordered `FreeBlockList`.
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+pub struct SbrkAllocator {
+    free_list: FreeBlockList,
+    list_heads: [Option<&'static mut FreeBlock>; BLOCK_SIZES.len()],
+    /// Current top of the heap, tracked from our own `sbrk` calls so we
+    /// know when a freed block sits at the break and can be handed back.
+    brk: usize,
+}
+
+pub struct Locked<T> {
+    inner: Spinlock<T>,
+}
+
+// Problem: MacOs Mutexes use pthreads, which are Box allocated!
+// So if we use std::sync::Mutex we create a loop here: The allocator's Mutex would require a Box which requires the allocator to work!
+// So we implement a custom stack based lock to avoid that
+impl<T> Locked<T> {
+    pub const fn new(inner: T) -> Self {
+        Locked {
+            inner: Spinlock::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> SpinlockGuard<T> {
+        self.inner.lock()
+    }
+}
+
+// rust doesn't allow implementing traits for external types. This maintains a property called coherence
+// So we must wrap our allocator in a Locked type ourselves
+unsafe impl GlobalAlloc for Locked<SbrkAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (size, align) = SbrkAllocator::align_layout(layout);
+        if let Some(ptr) = try_alloc_from_thread_cache(size, align) {
+            return ptr;
+        }
+        let mut l = self.lock();
+        l.malloc(size, align)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = SbrkAllocator::align_layout(layout);
+        if try_free_into_thread_cache(self, ptr, size) {
+            return;
+        }
+        let mut l = self.lock();
+        l.free(ptr, size)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let mut l = self.lock();
+        let (size, align) = SbrkAllocator::align_layout(layout);
+        let new_ptr = l.malloc(size, align);
+        if !new_ptr.is_null() {
+            new_ptr.write_bytes(0, layout.size());
+        }
+        new_ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let mut l = self.lock();
+        let (old_size, align) = SbrkAllocator::align_layout(layout);
+
+        let new_layout = match Layout::from_size_align(new_size, layout.align()) {
+            Ok(layout) => layout,
+            Err(_) => return ptr::null_mut(),
+        };
+        let (padded_new_size, _) = SbrkAllocator::align_layout(new_layout);
+
+        if padded_new_size <= old_size {
+            // shrinking (or no change): give back the excess tail rather than
+            // leaving it reserved, since the caller now tracks this block as
+            // only `padded_new_size` bytes and will never free more than that
+            l.shrink_block(ptr, old_size, padded_new_size);
+            return ptr;
+        }
+
+        if l.try_grow_in_place(ptr, old_size, padded_new_size) {
+            return ptr;
+        }
+
+        let new_ptr = l.malloc(padded_new_size, align);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+            l.free(ptr, old_size);
+        }
+        new_ptr
+    }
+}
+
+// Lets `Locked<SbrkAllocator>` back individual collections (`Vec::new_in`,
+// `Box::new_in`, ...) and not just the process-wide `#[global_allocator]`.
+// Its methods take `&self` rather than `&mut self`, which already matches
+// the `GlobalAlloc` impl above and the interior-mutability `Spinlock` design.
+unsafe impl Allocator for Locked<SbrkAllocator> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut l = self.lock();
+        let (size, align) = SbrkAllocator::align_layout(layout);
+        let ptr = unsafe { l.malloc(size, align) };
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        // Report the layout the caller asked for, not `size` (which is
+        // padded with the FreeBlock header reservation): callers track the
+        // returned length as their capacity and feed it back as the next
+        // call's layout, and re-padding an already-padded size overshoots
+        // the block's true footprint.
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut l = self.lock();
+        let (size, _) = SbrkAllocator::align_layout(layout);
+        l.free(ptr.as_ptr(), size)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let mut l = self.lock();
+        let (old_size, _) = SbrkAllocator::align_layout(old_layout);
+        let (new_size, new_align) = SbrkAllocator::align_layout(new_layout);
+
+        if new_size <= old_size || l.try_grow_in_place(ptr.as_ptr(), old_size, new_size) {
+            // same note as `allocate`: report `new_layout`'s own size, not
+            // the padded `new_size` used to size the underlying block
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
+
+        let new_ptr = l.malloc(new_size, new_align);
+        let new_ptr = NonNull::new(new_ptr).ok_or(AllocError)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), old_layout.size());
+        l.free(ptr.as_ptr(), old_size);
+        Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()))
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let mut l = self.lock();
+        let (old_size, _) = SbrkAllocator::align_layout(old_layout);
+        let (new_size, _) = SbrkAllocator::align_layout(new_layout);
+        // give back the excess tail rather than leaving it reserved: the
+        // caller now tracks this allocation as `new_layout`, so it will
+        // never deallocate more than `new_size`
+        l.shrink_block(ptr.as_ptr(), old_size, new_size);
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+}
+
+/// Number of blocks a per-thread cache bucket holds before it flushes half
+/// of them back to the global segregated lists.
+const THREAD_CACHE_FLUSH_LEN: usize = 32;
+
+/// Heap-free, per-thread front end for the segregated buckets. Lives in
+/// real TLS storage so `alloc`/`dealloc` on the hot path for small,
+/// same-sized objects never touch the global `Spinlock`.
+struct ThreadCache {
+    heads: [Option<&'static mut FreeBlock>; BLOCK_SIZES.len()],
+    lens: [usize; BLOCK_SIZES.len()],
+}
+
+impl ThreadCache {
+    const fn new() -> Self {
+        Self {
+            heads: [None, None, None, None, None, None, None, None, None],
+            lens: [0; BLOCK_SIZES.len()],
+        }
+    }
+
+    /// Unlinks up to `count` blocks from bucket `index` and relinks them
+    /// onto the global allocator's segregated list for that same bucket.
+    unsafe fn flush_bucket(&mut self, index: usize, count: usize, global: &Locked<SbrkAllocator>) {
+        let mut l = global.lock();
+        for _ in 0..count {
+            let blk = match self.heads[index].take() {
+                Some(blk) => blk,
+                None => break,
+            };
+            self.heads[index] = blk.next.take();
+            self.lens[index] -= 1;
+
+            let node_ptr = blk as *mut FreeBlock;
+            (*node_ptr).next = l.list_heads[index].take();
+            l.list_heads[index] = Some(&mut *node_ptr);
+        }
+    }
+
+    /// Drains every bucket into the global allocator; called from the
+    /// pthread destructor when the owning thread exits.
+    unsafe fn flush_all(&mut self, global: &Locked<SbrkAllocator>) {
+        for index in 0..BLOCK_SIZES.len() {
+            self.flush_bucket(index, self.lens[index], global);
+        }
+    }
+}
+
+#[thread_local]
+static mut THREAD_CACHE: ThreadCache = ThreadCache::new();
+
+/// Forms the `&mut ThreadCache` callers need via `addr_of_mut!` rather than
+/// a bare reference to the `static mut` itself, which `rustc`'s
+/// `static_mut_refs` lint flags even for a simple field access.
+unsafe fn thread_cache() -> &'static mut ThreadCache {
+    &mut *ptr::addr_of_mut!(THREAD_CACHE)
+}
+
+/// The `Locked<SbrkAllocator>` that per-thread caches flush into on thread
+/// exit. This front end is built for the single process-wide
+/// `#[global_allocator]`; the first thread to cache a freed block claims
+/// it here, so mixing several independent `SbrkAllocator` instances behind
+/// thread caches isn't supported.
+static OWNING_ALLOCATOR: AtomicPtr<Locked<SbrkAllocator>> =
+    AtomicPtr::new(ptr::null_mut());
+
+static TLS_KEY_INIT: Once = Once::new();
+static mut TLS_KEY: pthread_key_t = 0;
+
+/// pthread TLS destructor: runs once per thread at exit, flushing whatever
+/// that thread still had cached into the global allocator.
+extern "C" fn flush_thread_cache_on_exit(_value: *mut c_void) {
+    unsafe {
+        let global = OWNING_ALLOCATOR.load(Ordering::Relaxed);
+        if let Some(global) = global.as_ref() {
+            thread_cache().flush_all(global);
+        }
+    }
+}
+
+/// Ensures this thread's cache will be flushed when the thread exits.
+/// Creates the process-wide pthread key on first use, then gives this
+/// thread's slot a non-null value so its destructor actually fires later.
+unsafe fn register_thread_cache(global: &Locked<SbrkAllocator>) {
+    OWNING_ALLOCATOR
+        .compare_exchange(
+            ptr::null_mut(),
+            global as *const Locked<SbrkAllocator> as *mut Locked<SbrkAllocator>,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        )
+        .ok();
+
+    TLS_KEY_INIT.call_once(|| {
+        pthread_key_create(ptr::addr_of_mut!(TLS_KEY), Some(flush_thread_cache_on_exit));
+    });
+    if pthread_getspecific(TLS_KEY).is_null() {
+        pthread_setspecific(TLS_KEY, ptr::addr_of!(THREAD_CACHE) as *const c_void);
+    }
+}
+
+/// Pops a block for `size`/`align` straight out of this thread's cache.
+/// Returns `None` on a cache miss or an oversized request, so the caller
+/// falls through to the global, locked allocator.
+unsafe fn try_alloc_from_thread_cache(size: usize, align: usize) -> Option<*mut u8> {
+    let required = cmp::max(size, align);
+    let index = SbrkAllocator::list_index(required)?;
+    let cache = thread_cache();
+    // same caveat as the global list_heads: a bucket can hold blocks carved
+    // for a smaller alignment, so only take the head if it fits `align`,
+    // leaving it cached and falling through to the global allocator otherwise
+    let head_fits_align = cache.heads[index]
+        .as_deref()
+        .is_some_and(|blk| blk.start_addr() % align == 0);
+    if !head_fits_align {
+        return None;
+    }
+    let blk = cache.heads[index].take().unwrap();
+    cache.heads[index] = blk.next.take();
+    cache.lens[index] -= 1;
+    Some(blk as *mut FreeBlock as *mut u8)
+}
+
+/// Pushes a freed block into this thread's cache if `size` maps to a
+/// bucket, flushing half the bucket to `global` first if the cache has
+/// grown past `THREAD_CACHE_FLUSH_LEN`. Returns `false` for sizes that
+/// don't fit a bucket, leaving the cache untouched.
+unsafe fn try_free_into_thread_cache(global: &Locked<SbrkAllocator>, ptr: *mut u8, size: usize) -> bool {
+    let index = match SbrkAllocator::list_index(size) {
+        Some(index) => index,
+        None => return false,
+    };
+
+    register_thread_cache(global);
+
+    if thread_cache().lens[index] >= THREAD_CACHE_FLUSH_LEN {
+        thread_cache().flush_bucket(index, THREAD_CACHE_FLUSH_LEN / 2, global);
+    }
+
+    let mut node = FreeBlock::new(BLOCK_SIZES[index]);
+    let cache = thread_cache();
+    node.next = cache.heads[index].take();
+    let node_ptr = ptr as *mut FreeBlock;
+    node_ptr.write(node);
+    cache.heads[index] = Some(&mut *node_ptr);
+    cache.lens[index] += 1;
+    true
+}
+
+impl SbrkAllocator {
+    pub const fn new() -> Self {
+        Self {
+            free_list: FreeBlockList::new(),
+            list_heads: [None, None, None, None, None, None, None, None, None],
+            brk: 0,
+        }
+    }
+
+    /// Aligns the input layout in order to make sure we can allocate
+    /// a FreeBlock on the new memory region. Doesn't touch any allocator
+    /// state, so callers can use it to size a request before taking the lock.
+    fn align_layout(layout: Layout) -> (usize, usize) {
+        let max_align = cmp::max(align_of::<FreeBlock>(), layout.align());
+        let out_size = utils::to_align(layout.size() + size_of::<FreeBlock>(), max_align);
+        (out_size, max_align)
+    }
+
+    /// Smallest amount of heap to request from the OS at once when the free
+    /// list can't satisfy an allocation, so a run of small mallocs doesn't
+    /// cost a separate `sbrk` syscall each.
+    const MIN_CHUNK: usize = 64 * 1024;
+
+    /// Minimum size of a trailing free region at the top of the heap before
+    /// we bother giving it back via `sbrk`, so small frees near the break
+    /// don't thrash the break back and forth.
+    const SHRINK_THRESHOLD: usize = 4 * 1024;
+
+    /// Grows the break by `size` bytes and returns the start of the new
+    /// region, aligned to at least `align_of::<FreeBlock>()` (every FreeBlock
+    /// header needs that much) and to `align` on top of that, since this
+    /// region may be handed straight back as a live allocation. The OS
+    /// doesn't promise the program break itself is aligned, so any padding
+    /// needed is just eaten from the break; `size` is always a multiple of
+    /// the alignment already (see `align_layout`/`MIN_CHUNK`/`BLOCK_SIZES`),
+    /// so the break stays aligned to it from here on.
+    unsafe fn request_sys_mem(&mut self, size: isize, align: usize) -> *mut u8 {
+        let align = cmp::max(align, align_of::<FreeBlock>());
+        let current_break = sbrk(0) as usize;
+        let aligned_base = utils::to_align(current_break, align);
+        let padding = (aligned_base - current_break) as isize;
+
+        let ptr = sbrk(size + padding) as isize;
+        assert_ne!(ptr, -1); // sbrk returns pointer to -1 if it fails
+        let base = ptr + padding;
+        self.brk = (base + size) as usize;
+        base as *mut u8
+    }
+
+    /// Grows the break by at least `size`, rounded up to `MIN_CHUNK`, and
+    /// returns the first `size` bytes aligned to `align`; any leftover tail
+    /// is handed to the free list so later allocations are served without
+    /// touching `sbrk`.
+    unsafe fn grow_heap_for(&mut self, size: usize, align: usize) -> *mut u8 {
+        let chunk_size = cmp::max(size, Self::MIN_CHUNK);
+        let base = self.request_sys_mem(chunk_size as isize, align);
+
+        let leftover = chunk_size - size;
+        if leftover >= mem::size_of::<FreeBlock>() {
+            self.free_list.add_free_block(base.add(size), leftover);
+        }
+        base
+    }
+
+    /// If coalescing a just-freed block left a large enough region sitting
+    /// at the very top of the heap, hands it back to the OS instead of
+    /// keeping it on the free list, so long-running frees don't leave the
+    /// process pinned at its high-water mark.
+    unsafe fn maybe_shrink_heap(&mut self) {
+        if let Some(size) = self
+            .free_list
+            .take_trailing_block(self.brk, Self::SHRINK_THRESHOLD)
+        {
+            let ret = sbrk(-(size as isize)) as isize;
+            assert_ne!(ret, -1);
+            self.brk -= size;
+        }
+    }
+
+    /// Index into `BLOCK_SIZES`/`list_heads` of the smallest bucket that
+    /// can hold `size`, or `None` if it's bigger than the largest bucket
+    fn list_index(size: usize) -> Option<usize> {
+        BLOCK_SIZES.iter().position(|&bucket| bucket >= size)
+    }
+
+    /// Carves a fresh `block_size`-sized block for a segregated list whose
+    /// cache is empty, out of the general free list (falling back to sbrk)
+    unsafe fn carve_fixed_block(&mut self, block_size: usize, align: usize) -> *mut u8 {
+        match self.free_list.find_free_block(block_size, align) {
+            Some((blk, addr)) => {
+                let end = blk
+                    .start_addr()
+                    .checked_add(block_size)
+                    .expect("overflow error");
+                let excess = blk.end_addr() - end;
+                if excess > 0 {
+                    self.free_list.add_free_block(end as *mut u8, excess);
+                }
+                addr as *mut u8
+            }
+            None => self.grow_heap_for(block_size, align),
+        }
+    }
+
+    unsafe fn malloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let required = cmp::max(size, align);
+        if let Some(index) = Self::list_index(required) {
+            // a bucket is keyed on max(size, align), so it can hold blocks
+            // carved for a smaller alignment than this call needs; only pop
+            // the head if it actually satisfies `align`, otherwise carve a
+            // fresh block and leave the cached one for a call it does fit
+            let head_fits_align = self.list_heads[index]
+                .as_deref()
+                .is_some_and(|blk| blk.start_addr() % align == 0);
+            if head_fits_align {
+                let blk = self.list_heads[index].take().unwrap();
+                self.list_heads[index] = blk.next.take();
+                return blk as *mut FreeBlock as *mut u8;
+            }
+            return self.carve_fixed_block(BLOCK_SIZES[index], align);
+        }
+
+        match self.free_list.find_free_block(size, align) {
+            Some((blk, addr)) => {
+                let end = blk.start_addr().checked_add(size).expect("overflow error");
+                let excess = blk.end_addr() - end;
+                if excess > 0 {
+                    self.free_list.add_free_block(end as *mut u8, excess); // If the found blk is larger than we need, allocate the rest of it as a FreeBlock
+                }
+                addr as *mut u8
+            }
+            None => self.grow_heap_for(size, align),
+        }
+    }
+
+    pub unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        match Self::list_index(size) {
+            Some(index) => {
+                let mut node = FreeBlock::new(BLOCK_SIZES[index]);
+                node.next = self.list_heads[index].take();
+                let node_ptr = ptr as *mut FreeBlock;
+                node_ptr.write(node);
+                self.list_heads[index] = Some(&mut *node_ptr);
+            }
+            None => {
+                self.free_list.add_free_block(ptr, size);
+                self.maybe_shrink_heap();
+            }
+        }
+    }
+
+    /// Splits the tail between `new_size` and `old_size` off `ptr`'s block
+    /// and gives it back to the free list, rather than leaving it reserved:
+    /// once the caller tracks this block as only `new_size` bytes, it will
+    /// never free more than that, so the excess would otherwise be leaked.
+    unsafe fn shrink_block(&mut self, ptr: *mut u8, old_size: usize, new_size: usize) {
+        let excess = old_size - new_size;
+        if excess >= mem::size_of::<FreeBlock>() {
+            self.free_list.add_free_block(ptr.add(new_size), excess);
+            self.maybe_shrink_heap();
+        }
+    }
+
+    /// Tries to grow the block at `ptr` (currently `old_size` bytes) to
+    /// `new_size` bytes without moving it, either by absorbing an
+    /// adjacent free block or, if `ptr` is the topmost allocation on the
+    /// heap, by growing the break. Returns `false` if neither applies, in
+    /// which case the caller must alloc a new block, copy, and free `ptr`.
+    unsafe fn try_grow_in_place(&mut self, ptr: *mut u8, old_size: usize, new_size: usize) -> bool {
+        let addr = ptr as usize + old_size;
+        let needed = new_size - old_size;
+
+        if self.free_list.try_consume_at(addr, needed) {
+            return true;
+        }
+
+        if addr == self.brk {
+            // nothing follows this allocation; just push the break out.
+            // `addr` is already the end of a live, properly aligned block,
+            // so no extra alignment is needed here beyond FreeBlock's own.
+            self.request_sys_mem(needed as isize, align_of::<FreeBlock>());
+            return true;
+        }
+
+        false
+    }
+}
+
+pub struct FreeBlockList {
+    head: FreeBlock,
+}
+
+impl FreeBlockList {
+    const fn new() -> Self {
+        Self {
+            head: FreeBlock::new(0),
+        }
+    }
+
+    /// Inserts a new FreeBlock in address order and coalesces it with an
+    /// immediately adjacent predecessor and/or successor, so two physically
+    /// contiguous free regions never stay linked as separate nodes.
+    unsafe fn add_free_block(&mut self, ptr: *mut u8, size: usize) {
+        let new_start = ptr as usize;
+        let new_end = new_start + size;
+        let head_ptr: *const FreeBlock = &self.head;
+
+        // walk until `current`'s successor is at or past the new block, so
+        // the new block belongs right after `current`
+        let mut current = &mut self.head;
+        while let Some(ref succ) = current.next {
+            if succ.start_addr() >= new_start {
+                break;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+
+        let current_ptr: *const FreeBlock = current;
+        let merges_prev = current_ptr != head_ptr && current.end_addr() == new_start;
+
+        if merges_prev {
+            // extend the predecessor in place, then see if it now also
+            // touches its successor and can absorb it too
+            current.size += size;
+            if let Some(ref succ) = current.next {
+                if current.end_addr() == succ.start_addr() {
+                    let absorbed = current.next.take().unwrap();
+                    current.size += absorbed.size;
+                    current.next = absorbed.next.take();
+                }
+            }
+            return;
+        }
+
+        let merges_next = current
+            .next
+            .as_ref()
+            .is_some_and(|succ| new_end == succ.start_addr());
+
+        let node_ptr = ptr as *mut FreeBlock;
+        if merges_next {
+            // absorb the successor into a freshly written node at the new
+            // block's address, which is now its start address
+            let succ = current.next.take().unwrap();
+            let merged_size = size + succ.size;
+            let mut node = FreeBlock::new(merged_size);
+            node.next = succ.next.take();
+            node_ptr.write(node);
+        } else {
+            let mut node = FreeBlock::new(size);
+            node.next = current.next.take();
+            node_ptr.write(node);
+        }
+        current.next = Some(&mut *node_ptr);
+    }
+
+    /// If the highest-address block (the list is kept address-ordered, so
+    /// this is the last node) ends exactly at `brk` and is at least
+    /// `threshold` bytes, unlinks it and returns its size. Leaves the list
+    /// untouched otherwise.
+    fn take_trailing_block(&mut self, brk: usize, threshold: usize) -> Option<usize> {
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|n| n.next.is_some()) {
+            current = current.next.as_mut().unwrap();
+        }
+
+        match current.next {
+            Some(ref blk) if blk.end_addr() == brk && blk.size >= threshold => {
+                let size = blk.size;
+                current.next = None;
+                Some(size)
+            }
+            _ => None,
+        }
+    }
+
+    /// If a free block starts exactly at `addr` and is at least `needed`
+    /// bytes, unlinks it (re-inserting any leftover as a smaller free
+    /// block at the same address) and returns `true`. The list is
+    /// address-ordered, so the scan can stop as soon as it passes `addr`.
+    unsafe fn try_consume_at(&mut self, addr: usize, needed: usize) -> bool {
+        let mut current = &mut self.head;
+        while let Some(ref blk) = current.next {
+            if blk.start_addr() == addr {
+                if blk.size < needed {
+                    return false;
+                }
+                break;
+            }
+            if blk.start_addr() > addr {
+                return false;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+
+        let blk = match current.next.take() {
+            Some(blk) => blk,
+            None => return false,
+        };
+        current.next = blk.next.take();
+
+        let excess = blk.size - needed;
+        if excess >= mem::size_of::<FreeBlock>() {
+            self.add_free_block((addr + needed) as *mut u8, excess);
+        }
+        true
+    }
+
+    fn find_free_block(
+        &mut self,
+        size: usize,
+        align: usize,
+    ) -> Option<(&'static mut FreeBlock, usize)> {
+        let mut current = &mut self.head;
+
+        while let Some(ref mut blk) = current.next {
+            if let Ok(start_addr) = Self::check_block(blk, size, align) {
+                let next = blk.next.take();
+                let ret = Some((current.next.take().unwrap(), start_addr));
+                current.next = next;
+                return ret;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+        None // if we reach the end of list, no more free memory
+    }
+
+    /// Checks if block is suitable for allocation of size `size`
+    fn check_block(block: &FreeBlock, size: usize, align: usize) -> Result<usize, ()> {
+        let alloc_start = utils::to_align(block.start_addr(), align);
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+
+        if alloc_end > block.end_addr() {
+            // region too small
+            return Err(());
+        }
+
+        // size for Freeblock
+        let excess_size = block.end_addr() - alloc_end;
+        if excess_size > 0 && excess_size < mem::size_of::<FreeBlock>() {
+            // rest of region too small to hold a FreeBlock
+            // if the current FreeBlock is too large, we can allocate only `size`
+            // and then allocate a FreeBlock for the rest of it so we use the resource well
+            return Err(());
+        }
+
+        Ok(alloc_start)
+    }
+}
+
+pub struct FreeBlock {
+    size: usize,
+    next: Option<&'static mut FreeBlock>,
+}
+
+impl FreeBlock {
+    const fn new(size: usize) -> Self {
+        FreeBlock { size, next: None } // needs #![feature(const_mut_refs)] -> unstable
+    }
+
+    // does this work because the heap starts on the end of the stack?
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+mod tests {
+
+    pub struct Thunk {
+        field1: u32,
+        field2: [u32; 10],
+    }
+    // 4 bytes
+    #[derive(Debug)]
+    pub struct SmallThunk {
+        field1: u32,
+    }
+
+    #[cfg(feature = "use_sbrk_allocator")]
+    #[cfg(test)]
+    mod tests_allocator {
+        use std::alloc::{alloc, Layout};
+        use super::{Thunk, SmallThunk};
+        #[test]
+        fn test_alloc_base() {
+            // this doesn't work if SbrkAllocator itself is the global allocator. We might mess with it's data
+            // let mut alloc = SbrkAllocator::new();
+    
+            let size = size_of::<Thunk>();
+            let align = align_of::<Thunk>();
+    
+            if let Ok(layout) = Layout::from_size_align(size, align) {
+                let ref_t = unsafe {
+                    let test_t = alloc(layout) as *mut Thunk;
+                    &*test_t
+                };
+                assert_eq!(size_of_val(ref_t), size_of::<Thunk>());
+                assert_eq!(align_of_val(ref_t), align_of::<Thunk>());
+                return;
+            }
+            panic!("Couldn't get layout");
+        }
+    }
+    
+    #[cfg(not(feature = "use_sbrk_allocator"))]
+    /// We can't run tests in the internal SbrkAllocator if we set the global allocator to it, as we'd have two
+    /// independent allocators managing the heap via SBRK, which breaks the allocator. So the `use_sbrk_allocator` feature selects the tests that can run
+    /// when the global allocator isn't set.
+    #[cfg(test)]
+    mod test_internals {
+        use super::super::{Locked, SbrkAllocator, FreeBlock, BLOCK_SIZES};
+        use super::{Thunk, SmallThunk};
+    
+        #[test]
+        fn test_malloc_excess() {
+            // sizes above the largest segregated bucket (2048), and multiples
+            // of align_of::<FreeBlock>() like every real caller's padded size
+            // (see align_layout), so this still exercises the general
+            // FreeBlockList's excess-carving path
+            let large_size = 3000;
+            let small_size = 2096;
+            let align = align_of::<Thunk>();
+            let mut alloc = SbrkAllocator::new();
+            unsafe {
+                let large_value_addr = alloc.malloc(large_size, align);
+                alloc.free(large_value_addr, large_size);
+
+                // Allocates a region smaller than the freed one. So the FreeBlock will have excess space that must remain free
+                let _small_value_addr = alloc.malloc(small_size, align);
+
+                assert!(alloc.free_list.head.next.is_some_and(|b| {
+                    // The first FreeBlock should start in the excess space of the second allocation
+                    let excess_offset = large_value_addr as u8 + small_size as u8;
+                    b.start_addr() as u8 == excess_offset
+                }));
+            };
+        }
+
+        // Test to show memory reuse. After freeing the first pointer, we ask for another object.
+        // Both mallocs map to the same segregated bucket, so the freed block comes right back.
+        #[test]
+        fn test_free_reuse() {
+            unsafe {
+                let mut alloc = SbrkAllocator::new();
+
+                let first_ptr = alloc.malloc(size_of::<Thunk>(), align_of::<Thunk>()) as *mut Thunk;
+
+                let _second_ptr = alloc.malloc(size_of::<Thunk>(), align_of::<Thunk>()) as *mut Thunk;
+
+                alloc.free(first_ptr as *mut u8, size_of::<Thunk>());
+
+                let third_ptr = alloc.malloc(size_of::<Thunk>(), align_of::<Thunk>()) as *mut Thunk;
+                assert_eq!(first_ptr, third_ptr);
+            }
+        }
+
+        // A segregated bucket can hold a block carved under a looser
+        // alignment than a later call with the same size needs (both map to
+        // the same bucket via list_index(max(size, align))). Popping that
+        // block without checking its actual address would hand back memory
+        // that doesn't satisfy the stricter request.
+        #[test]
+        fn test_malloc_skips_cached_block_with_wrong_alignment() {
+            let mut alloc = SbrkAllocator::new();
+            unsafe {
+                // carve a real, 8-aligned backing region out of sbrk so the
+                // seeded address below is a genuine heap address
+                let base = alloc.malloc(4096, align_of::<Thunk>());
+                alloc.free(base, 4096);
+
+                // an address that's 8-aligned but not 16-aligned
+                let misaligned = if base as usize % 16 == 0 {
+                    base.add(8)
+                } else {
+                    base
+                };
+                let index = SbrkAllocator::list_index(32).unwrap();
+                let node_ptr = misaligned as *mut FreeBlock;
+                node_ptr.write(FreeBlock::new(BLOCK_SIZES[index]));
+                alloc.list_heads[index] = Some(&mut *node_ptr);
+
+                let ptr = alloc.malloc(32, 16);
+                assert_ne!(ptr, misaligned);
+                assert_eq!(ptr as usize % 16, 0);
+            }
+        }
+
+        // Two different small sizes that map to the same segregated bucket should
+        // reuse the same freed block in O(1), without touching the general free list
+        #[test]
+        fn test_segregated_list_reuse() {
+            let mut alloc = SbrkAllocator::new();
+            unsafe {
+                let first_ptr = alloc.malloc(size_of::<SmallThunk>(), align_of::<SmallThunk>());
+                alloc.free(first_ptr, size_of::<SmallThunk>());
+
+                let second_ptr = alloc.malloc(6, align_of::<SmallThunk>());
+                assert_eq!(first_ptr, second_ptr);
+            }
+        }
+
+        // A block that sits at the very top of the heap (nothing allocated
+        // after it) should grow in place by just pushing the break out
+        #[test]
+        fn test_try_grow_in_place_at_heap_top() {
+            let mut alloc = SbrkAllocator::new();
+            unsafe {
+                // consumes the whole chunk sbrk hands back, leaving no trailing free block
+                let size = SbrkAllocator::MIN_CHUNK;
+                let ptr = alloc.malloc(size, align_of::<SmallThunk>());
+                let old_brk = alloc.brk;
+                assert_eq!(ptr as usize + size, old_brk);
+
+                assert!(alloc.try_grow_in_place(ptr, size, size + 100));
+                assert_eq!(alloc.brk, old_brk + 100);
+            }
+        }
+
+        // A free block immediately following an allocation should be
+        // consumable in place, without moving the allocation
+        #[test]
+        fn test_free_block_list_consume_adjacent() {
+            let mut alloc = SbrkAllocator::new();
+            unsafe {
+                let base = alloc.malloc(64, align_of::<SmallThunk>());
+                // the fixed-size carve left a large free block right after `base`
+                assert!(alloc.free_list.try_consume_at(base as usize + 64, 32));
+            }
+        }
+
+        // Freeing three physically adjacent blocks out of address order
+        // (middle, then left, then right) should leave the free list with a
+        // single coalesced block spanning all three, exercising both the
+        // merges_prev and merges_next paths in add_free_block
+        #[test]
+        fn test_add_free_block_coalesces_adjacent_and_stays_address_ordered() {
+            let mut alloc = SbrkAllocator::new();
+            unsafe {
+                // above the largest segregated bucket, so these are carved
+                // straight out of the general free list with no gaps between them
+                let size = 3000;
+                let align = align_of::<Thunk>();
+                let a = alloc.malloc(size, align);
+                let b = alloc.malloc(size, align);
+                let c = alloc.malloc(size, align);
+
+                // call add_free_block directly (rather than alloc.free) so this
+                // only exercises insertion/coalescing, not the heap-shrink path
+                alloc.free_list.add_free_block(b, size);
+                alloc.free_list.add_free_block(a, size);
+                alloc.free_list.add_free_block(c, size);
+
+                // a, b and c, plus the chunk's leftover tail, all coalesce back
+                // into the single free block the chunk started as
+                assert!(alloc.free_list.head.next.as_ref().is_some_and(|blk| {
+                    blk.start_addr() == a as usize
+                        && blk.size == SbrkAllocator::MIN_CHUNK
+                        && blk.next.is_none()
+                }));
+            }
+        }
+
+        // Freeing a block big enough, and sitting right at the break, should
+        // hand the memory back to the OS instead of keeping it on the free list
+        #[test]
+        fn test_free_shrinks_heap_when_trailing_block_crosses_threshold() {
+            let mut alloc = SbrkAllocator::new();
+            unsafe {
+                let size = SbrkAllocator::SHRINK_THRESHOLD + 1024;
+                let ptr = alloc.malloc(size, align_of::<SmallThunk>());
+                let brk_after_malloc = alloc.brk;
+
+                alloc.free(ptr, size);
+
+                // the freed block coalesced with the chunk's leftover tail,
+                // reached the break, and crossed SHRINK_THRESHOLD, so it was
+                // returned via sbrk rather than kept on the list
+                assert!(alloc.brk < brk_after_malloc);
+                assert!(alloc.free_list.head.next.is_none());
+            }
+        }
+
+        // Shrinking via GlobalAlloc::realloc must give the excess tail back
+        // to the free list, not just leave it reserved on a block the
+        // caller now tracks (and will only ever free) as the smaller size
+        #[test]
+        fn test_realloc_shrink_reclaims_excess_tail() {
+            use std::alloc::{GlobalAlloc, Layout};
+
+            let locked = Locked::new(SbrkAllocator::new());
+            let layout = Layout::from_size_align(3000, align_of::<Thunk>()).unwrap();
+            unsafe {
+                let ptr = locked.alloc(layout);
+                let shrunk = locked.realloc(ptr, layout, 2500);
+                assert_eq!(shrunk, ptr);
+
+                let (old_size, _) = SbrkAllocator::align_layout(layout);
+                let new_layout = Layout::from_size_align(2500, layout.align()).unwrap();
+                let (new_size, _) = SbrkAllocator::align_layout(new_layout);
+                let excess = old_size - new_size;
+
+                let l = locked.lock();
+                assert!(l
+                    .free_list
+                    .head
+                    .next
+                    .as_ref()
+                    .is_some_and(|blk| blk.size >= excess));
+            }
+        }
+
+        // A Vec backed by Locked<SbrkAllocator> via the Allocator trait should
+        // grow correctly across several reallocations, each of which reports
+        // back a capacity this allocator's own old_size/new_size bookkeeping
+        // in `grow` agrees with
+        #[test]
+        fn test_allocator_trait_vec_grows_and_keeps_contents() {
+            let locked = Locked::new(SbrkAllocator::new());
+            let mut v: Vec<u64, &Locked<SbrkAllocator>> = Vec::new_in(&locked);
+            for i in 0..256u64 {
+                v.push(i);
+            }
+            assert_eq!(v.len(), 256);
+            for (i, &value) in v.iter().enumerate() {
+                assert_eq!(value, i as u64);
+            }
+        }
+
+        // Shrinking via the Allocator trait must give the excess tail back
+        // to the free list, just like GlobalAlloc::realloc's shrink path
+        #[test]
+        fn test_allocator_shrink_reclaims_excess_tail() {
+            use std::alloc::{Allocator, Layout};
+
+            let locked = Locked::new(SbrkAllocator::new());
+            let old_layout = Layout::from_size_align(3000, align_of::<Thunk>()).unwrap();
+            let new_layout = Layout::from_size_align(2500, old_layout.align()).unwrap();
+
+            unsafe {
+                let ptr = locked.allocate(old_layout).unwrap().cast::<u8>();
+                locked.shrink(ptr, old_layout, new_layout).unwrap();
+
+                let (old_size, _) = SbrkAllocator::align_layout(old_layout);
+                let (new_size, _) = SbrkAllocator::align_layout(new_layout);
+                let excess = old_size - new_size;
+
+                let l = locked.lock();
+                assert!(l
+                    .free_list
+                    .head
+                    .next
+                    .as_ref()
+                    .is_some_and(|blk| blk.size >= excess));
+            }
+        }
+
+        // Freeing through GlobalAlloc::dealloc on a background thread caches
+        // the block in that thread's #[thread_local] cache rather than the
+        // global segregated list; once the thread exits, the pthread
+        // destructor should flush it back into this allocator's list
+        #[test]
+        fn test_thread_cache_flushes_to_global_on_thread_exit() {
+            use std::alloc::{GlobalAlloc, Layout};
+
+            let locked = Locked::new(SbrkAllocator::new());
+            let layout =
+                Layout::from_size_align(size_of::<SmallThunk>(), align_of::<SmallThunk>()).unwrap();
+
+            std::thread::scope(|scope| {
+                scope.spawn(|| unsafe {
+                    let ptr = locked.alloc(layout);
+                    locked.dealloc(ptr, layout);
+                });
+            });
+
+            // thread-cache sizing mirrors GlobalAlloc::{alloc,dealloc}: the
+            // cached bucket is keyed off align_layout's padded size, not the
+            // caller's raw layout size
+            let (size, _) = SbrkAllocator::align_layout(layout);
+            let index = SbrkAllocator::list_index(size).expect("fits a bucket");
+            let l = locked.lock();
+            assert!(l.list_heads[index].is_some());
+        }
+    }
+}
+
+
@@ -1,4 +1,6 @@
 #![feature(const_mut_refs)] // allows static mut refs
+#![feature(allocator_api)] // lets Locked<SbrkAllocator> back Vec::new_in/Box::new_in
+#![feature(thread_local)] // per-thread free-list caches in front of the global Spinlock
 use allocator::sbrk::{Locked, SbrkAllocator};
 use libc::sbrk;
 